@@ -22,6 +22,19 @@ pub enum External {
   geti,
   getc,
   seq,
+  div,
+  mod_,
+  and,
+  or,
+  xor,
+  shl,
+  shr,
+  newarr,
+  index,
+  update,
+  length,
+  putstr,
+  getstr,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -88,6 +101,19 @@ impl External {
       geti => 1,
       getc => 1,
       seq => 2,
+      div => 2,
+      mod_ => 2,
+      and => 2,
+      or => 2,
+      xor => 2,
+      shl => 2,
+      shr => 2,
+      newarr => 2,
+      index => 2,
+      update => 3,
+      length => 1,
+      putstr => 1,
+      getstr => 1,
     }
   }
 }
@@ -112,13 +138,21 @@ impl TopLevel {
   }
 }
 
+#[cfg(feature = "native")]
 pub fn load_module<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Module> {
   use std::fs::File;
   let file: File = File::open(path)?;
   let top_levels: Vec<TopLevel> = serde_json::from_reader(file)?;
-  let module: Module = top_levels
-    .into_iter()
-    .filter_map(TopLevel::lambda)
-    .collect();
-  Ok(module)
+  Ok(top_levels_to_module(top_levels))
+}
+
+/// Like `load_module`, but reads the JSON from a string instead of a file,
+/// so it works on targets without filesystem access (e.g. `wasm32`).
+pub fn load_module_str(json: &str) -> serde_json::Result<Module> {
+  let top_levels: Vec<TopLevel> = serde_json::from_str(json)?;
+  Ok(top_levels_to_module(top_levels))
+}
+
+fn top_levels_to_module(top_levels: Vec<TopLevel>) -> Module {
+  top_levels.into_iter().filter_map(TopLevel::lambda).collect()
 }
@@ -0,0 +1,140 @@
+use std::env;
+use std::io;
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use ruceki::ast::{self, Expr, Module};
+use ruceki::cek;
+
+const HISTORY_FILE: &str = ".ruceki_history";
+
+struct Repl {
+  path: String,
+  module: Module,
+  max_steps: Option<u64>,
+}
+
+impl Repl {
+  fn load(path: &str) -> io::Result<Self> {
+    let module = ast::load_module(path)?;
+    Ok(Repl {
+      path: path.to_string(),
+      module,
+      max_steps: None,
+    })
+  }
+
+  fn reload(&mut self) {
+    match ast::load_module(&self.path) {
+      Ok(module) => {
+        self.module = module;
+        println!("Reloaded {}", self.path);
+      }
+      Err(err) => println!("Failed to reload {}: {}", self.path, err),
+    }
+  }
+
+  fn list_globals(&self) {
+    let mut names: Vec<&String> = self.module.keys().collect();
+    names.sort();
+    for name in names {
+      println!("{}", name);
+    }
+  }
+
+  /// Parses `<global> <int> <int> ...`, mirroring `Expr::entry_point`, which
+  /// applies `main` to a single unit value. Requires at least one argument:
+  /// `cek::State::step` traps an `Ap` with zero arguments with
+  /// `RuntimeError::ArityMismatch`, so such a line is rejected here instead.
+  fn parse_call(line: &str) -> Option<Expr> {
+    let mut tokens = line.split_whitespace();
+    let name = tokens.next()?.to_string();
+    let mut args = Vec::new();
+    for token in tokens {
+      let int: i64 = token.parse().ok()?;
+      args.push(Expr::Num { int });
+    }
+    if args.is_empty() {
+      return None;
+    }
+    Some(Expr::Ap {
+      fun: Box::new(Expr::Global { name }),
+      args,
+    })
+  }
+
+  fn eval(&mut self, line: &str) {
+    let expr = match Self::parse_call(line) {
+      Some(expr) => expr,
+      None => {
+        println!("Expected: <global> <int> <int> ...");
+        return;
+      }
+    };
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut output = io::stdout();
+    let mut state = cek::State::from_expr(&expr, &mut input, &mut output);
+    state.set_max_steps(self.max_steps);
+    match state.run(&self.module) {
+      Ok((result, steps)) => println!("=> {:?} ({} steps)", result, steps),
+      Err(err) => println!("Trapped: {}", err),
+    }
+  }
+
+  fn handle_command(&mut self, command: &str) -> bool {
+    let mut parts = command.trim_start_matches(':').split_whitespace();
+    match parts.next() {
+      Some("reload") => self.reload(),
+      Some("globals") => self.list_globals(),
+      Some("steps") => match parts.next().and_then(|n| n.parse().ok()) {
+        Some(0) => self.max_steps = None,
+        Some(n) => {
+          self.max_steps = Some(n);
+          println!("Step budget set to {}", n);
+        }
+        None => println!("Usage: :steps <n> (0 clears the budget)"),
+      },
+      Some("quit") | Some("exit") => return true,
+      _ => println!("Unknown command: {}", command),
+    }
+    false
+  }
+}
+
+fn main() -> io::Result<()> {
+  let args: Vec<String> = env::args().collect();
+  let filename = &args[1];
+  let mut repl = Repl::load(filename)?;
+
+  let mut editor = DefaultEditor::new().expect("Failed to start line editor");
+  let _ = editor.load_history(HISTORY_FILE);
+
+  loop {
+    match editor.readline("ruceki> ") {
+      Ok(line) => {
+        let line = line.trim();
+        if line.is_empty() {
+          continue;
+        }
+        let _ = editor.add_history_entry(line);
+        if line.starts_with(':') {
+          if repl.handle_command(line) {
+            break;
+          }
+        } else {
+          repl.eval(line);
+        }
+      }
+      Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+      Err(err) => {
+        println!("Readline error: {}", err);
+        break;
+      }
+    }
+  }
+
+  let _ = editor.save_history(HISTORY_FILE);
+  Ok(())
+}
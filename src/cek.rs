@@ -1,44 +1,42 @@
 use std::borrow::Borrow;
-use std::rc::Rc;
+use std::io::{BufRead, Write};
 
 use crate::ast::*;
+use crate::error::RuntimeError;
+use crate::heap::{GcStats, Handle, Heap, DEFAULT_GC_THRESHOLD};
 use crate::val::{Prim, Value};
 
 #[derive(Debug)]
 enum Ctrl<'a> {
   Evaluating,
   Expr(&'a Expr),
-  Value(Rc<Value<'a>>),
+  Value(Handle),
 }
 
-impl<'a> Ctrl<'a> {
-  fn from_prim(prim: Prim<'a>, arity: usize) -> Self {
-    Ctrl::Value(Rc::new(Value::PAP(prim, Vec::new(), arity)))
-  }
-}
-
-#[derive(Debug)]
-struct Env<'a> {
-  stack: Vec<Rc<Value<'a>>>,
+#[derive(Debug, Default)]
+struct Env {
+  stack: Vec<Handle>,
 }
 
-impl<'a> Env<'a> {
+impl Env {
   fn new() -> Self {
     Env { stack: Vec::new() }
   }
 
-  fn get(&self, idx: usize) -> &Rc<Value<'a>> {
+  fn get(&self, idx: usize) -> Option<Handle> {
     self
       .stack
-      .get(self.stack.len() - idx)
-      .expect("Bad de Bruijn index")
+      .len()
+      .checked_sub(idx)
+      .and_then(|i| self.stack.get(i))
+      .copied()
   }
 
-  fn push(&mut self, value: Rc<Value<'a>>) {
+  fn push(&mut self, value: Handle) {
     self.stack.push(value);
   }
 
-  fn push_many(&mut self, args: &Vec<Rc<Value<'a>>>) {
+  fn push_many(&mut self, args: &[Handle]) {
     self.stack.extend_from_slice(args);
   }
 
@@ -50,50 +48,156 @@ impl<'a> Env<'a> {
 
 #[derive(Debug)]
 enum Kont<'a> {
-  Dump(Env<'a>),
+  Dump(Env),
   Pop(usize),
   Args(&'a [Expr]),
-  Fun(Prim<'a>, Vec<Rc<Value<'a>>>, usize),
+  Fun(Prim<'a>, Vec<Handle>, usize),
   Match(&'a Vec<Altn>),
   Let(&'a Name, &'a Expr),
 }
 
-#[derive(Debug)]
-pub struct State<'a> {
+/// The final result of a run, once `Ctrl` has settled on a concrete value.
+/// Unlike `Value`, this owns its data instead of pointing into the heap, so
+/// it can outlive the `State` that produced it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunResult {
+  Num(i64),
+  Pack(usize, Vec<RunResult>),
+  Array(Vec<RunResult>),
+}
+
+pub struct State<'a, In, Out> {
   ctrl: Ctrl<'a>,
-  env: Env<'a>,
+  env: Env,
   kont: Vec<Kont<'a>>,
+  heap: Heap<Prim<'a>>,
+  steps: u64,
+  max_steps: Option<u64>,
+  input: &'a mut In,
+  output: &'a mut Out,
 }
 
-impl<'a> State<'a> {
-  pub fn from_expr(expr: &'a Expr) -> Self {
+impl<'a, In: BufRead, Out: Write> State<'a, In, Out> {
+  pub fn from_expr(expr: &'a Expr, input: &'a mut In, output: &'a mut Out) -> Self {
+    Self::with_gc_threshold(expr, input, output, DEFAULT_GC_THRESHOLD)
+  }
+
+  pub fn with_gc_threshold(
+    expr: &'a Expr,
+    input: &'a mut In,
+    output: &'a mut Out,
+    gc_threshold: usize,
+  ) -> Self {
     State {
       ctrl: Ctrl::Expr(expr),
       env: Env::new(),
       kont: Vec::new(),
+      heap: Heap::new(gc_threshold),
+      steps: 0,
+      max_steps: None,
+      input,
+      output,
     }
   }
 
-  fn step(&mut self, module: &'a Module) {
+  pub fn gc_stats(&self) -> GcStats {
+    self.heap.gc_stats()
+  }
+
+  /// Aborts the run with `RuntimeError::StepLimit` instead of looping
+  /// forever once more than `max_steps` steps have been taken. Pass `None`
+  /// to run unbounded, which is the default.
+  pub fn set_max_steps(&mut self, max_steps: Option<u64>) {
+    self.max_steps = max_steps;
+  }
+
+  pub fn steps(&self) -> u64 {
+    self.steps
+  }
+
+  /// Every handle the garbage collector must treat as live: the value (if
+  /// any) currently under evaluation, every local on the environment stack,
+  /// and every handle saved in a continuation frame (a dumped environment's
+  /// locals, or a partially-applied function's accumulated arguments).
+  /// `Kont::Args` holds unevaluated expressions, not handles, so it
+  /// contributes nothing.
+  fn collect_roots(&self) -> Vec<Handle> {
+    let mut roots = Vec::new();
+    if let Ctrl::Value(handle) = &self.ctrl {
+      roots.push(*handle);
+    }
+    roots.extend_from_slice(&self.env.stack);
+    for k in &self.kont {
+      match k {
+        Kont::Dump(env) => roots.extend_from_slice(&env.stack),
+        Kont::Fun(_, args, _) => roots.extend_from_slice(args),
+        Kont::Pop(_) | Kont::Args(_) | Kont::Match(_) | Kont::Let(_, _) => {}
+      }
+    }
+    roots
+  }
+
+  fn maybe_collect(&mut self) {
+    if self.heap.should_collect() {
+      let roots = self.collect_roots();
+      self.heap.collect(roots);
+    }
+  }
+
+  pub(crate) fn step(&mut self, module: &'a Module) -> Result<(), RuntimeError<'a>> {
+    self.maybe_collect();
+    self.steps += 1;
+    if let Some(max_steps) = self.max_steps {
+      if self.steps > max_steps {
+        return Err(RuntimeError::StepLimit { step: self.steps });
+      }
+    }
+
     let old_ctrl = std::mem::replace(&mut self.ctrl, Ctrl::Evaluating);
 
     let new_ctrl = match old_ctrl {
       Ctrl::Evaluating => panic!("Control was not update after last step"),
 
-      Ctrl::Expr(Expr::Local { idx, .. }) => {
-        let v = self.env.get(*idx);
-        Ctrl::Value(Rc::clone(&v))
+      Ctrl::Expr(expr @ Expr::Local { idx, .. }) => match self.env.get(*idx) {
+        Some(handle) => Ctrl::Value(handle),
+        None => {
+          return Err(RuntimeError::BadLocalIndex {
+            expr,
+            step: self.steps,
+          })
+        }
+      },
+      Ctrl::Expr(expr @ Expr::Global { name }) => match module.get(name) {
+        Some(lam) => {
+          let handle = self
+            .heap
+            .alloc(Value::PAP(Prim::Global(name, lam), Vec::new(), lam.binds.len()));
+          Ctrl::Value(handle)
+        }
+        None => {
+          return Err(RuntimeError::UnknownGlobal {
+            expr,
+            step: self.steps,
+          })
+        }
+      },
+      Ctrl::Expr(Expr::External { name }) => {
+        let handle = self
+          .heap
+          .alloc(Value::PAP(Prim::External(*name), Vec::new(), name.arity()));
+        Ctrl::Value(handle)
       }
-      Ctrl::Expr(Expr::Global { name }) => {
-        let lam = module
-          .get(name)
-          .expect(&format!("Unknown global: {}", name));
-        Ctrl::from_prim(Prim::Global(name, lam), lam.binds.len())
+      Ctrl::Expr(&Expr::Pack { tag, arity }) => {
+        let handle = self
+          .heap
+          .alloc(Value::PAP(Prim::Pack(tag, arity), Vec::new(), arity));
+        Ctrl::Value(handle)
       }
-      Ctrl::Expr(Expr::External { name }) => Ctrl::from_prim(Prim::External(*name), name.arity()),
-      Ctrl::Expr(&Expr::Pack { tag, arity }) => Ctrl::from_prim(Prim::Pack(tag, arity), arity),
-      Ctrl::Expr(&Expr::Num { int }) => Ctrl::Value(Value::rc_from_i64(int)),
+      Ctrl::Expr(&Expr::Num { int }) => Ctrl::Value(self.heap.alloc(Value::Num(int))),
       Ctrl::Expr(Expr::Ap { fun, args }) => {
+        if args.is_empty() {
+          return Err(RuntimeError::ArityMismatch { step: self.steps });
+        }
         self.kont.push(Kont::Args(args));
         Ctrl::Expr(fun)
       }
@@ -107,81 +211,217 @@ impl<'a> State<'a> {
         Ctrl::Expr(expr)
       }
 
-      Ctrl::Value(v) => match v.borrow() {
-        Value::PAP(prim, args, 0) => match prim {
-          Prim::Global(_name, lam) => {
-            let Lambda { body, .. } = lam;
-            let mut new_env = Env::new();
-            new_env.push_many(args);
-            let old_env = std::mem::replace(&mut self.env, new_env);
-            self.kont.push(Kont::Dump(old_env));
-            Ctrl::Expr(body)
+      Ctrl::Value(handle) => match self.heap.get(handle) {
+        Value::PAP(prim, args, 0) => {
+          let prim = *prim;
+          match prim {
+            Prim::Global(_name, lam) => {
+              let Lambda { body, .. } = lam;
+              let mut new_env = Env::new();
+              new_env.push_many(args);
+              let old_env = std::mem::replace(&mut self.env, new_env);
+              self.kont.push(Kont::Dump(old_env));
+              Ctrl::Expr(body)
+            }
+            Prim::External(name) => {
+              let args = args.clone();
+              let result = Value::eval_external(
+                &mut self.heap,
+                name,
+                &args,
+                self.input,
+                self.output,
+                self.steps,
+              )?;
+              Ctrl::Value(result)
+            }
+            Prim::Pack(tag, _arity) => {
+              let args = args.clone();
+              Ctrl::Value(self.heap.alloc(Value::Pack(tag, args)))
+            }
           }
-          Prim::External(name) => Ctrl::Value(Value::eval_external(*name, &args)),
-          Prim::Pack(tag, _arity) => Ctrl::Value(Rc::new(Value::Pack(*tag, args.clone()))),
-        },
+        }
 
         _ => match self.kont.pop().expect("Step on final state") {
           Kont::Dump(env) => {
             self.env = env;
-            Ctrl::Value(Rc::clone(&v))
+            Ctrl::Value(handle)
           }
           Kont::Pop(count) => {
             self.env.pop(count);
-            Ctrl::Value(Rc::clone(&v))
+            Ctrl::Value(handle)
           }
-          Kont::Args(next_args) => match v.borrow() {
+          Kont::Args(next_args) => match self.heap.get(handle) {
             Value::PAP(prim, args, missing) => {
-              let (next_arg, next_args) = next_args.split_first().expect("Empty Args");
+              let prim = *prim;
+              let args = args.clone();
+              let missing = *missing;
+              let (next_arg, next_args) = match next_args.split_first() {
+                Some(split) => split,
+                None => return Err(RuntimeError::ArityMismatch { step: self.steps }),
+              };
               if !next_args.is_empty() {
                 self.kont.push(Kont::Args(next_args));
               }
-              self.kont.push(Kont::Fun(*prim, args.clone(), *missing));
+              self.kont.push(Kont::Fun(prim, args, missing));
               Ctrl::Expr(next_arg)
             }
-            _ => panic!("Applying value"),
+            _ => return Err(RuntimeError::NotAFunction { step: self.steps }),
           },
           Kont::Fun(prim2, mut args2, missing2) => {
-            args2.push(Rc::clone(&v));
-            Ctrl::Value(Rc::new(Value::PAP(prim2, args2, missing2 - 1)))
+            args2.push(handle);
+            Ctrl::Value(self.heap.alloc(Value::PAP(prim2, args2, missing2 - 1)))
           }
-          Kont::Match(altns) => match v.borrow() {
-            Value::Pack(tag, args) => {
-              let Altn { rhs, .. } = &altns[*tag];
-              self.kont.push(Kont::Pop(args.len()));
-              self.env.push_many(&args);
-              Ctrl::Expr(rhs)
-            }
-            _ => panic!("Pattern match on non-data value"),
+          Kont::Match(altns) => match self.heap.get(handle) {
+            Value::Pack(tag, args) => match altns.get(*tag) {
+              Some(Altn { rhs, .. }) => {
+                self.kont.push(Kont::Pop(args.len()));
+                self.env.push_many(args);
+                Ctrl::Expr(rhs)
+              }
+              None => {
+                return Err(RuntimeError::TagOutOfRange {
+                  tag: *tag,
+                  step: self.steps,
+                })
+              }
+            },
+            _ => return Err(RuntimeError::NotData { step: self.steps }),
           },
           Kont::Let(_name, body) => {
             self.kont.push(Kont::Pop(1));
-            self.env.push(Rc::clone(&v));
+            self.env.push(handle);
             Ctrl::Expr(body)
           }
         },
       },
     };
 
-    self.ctrl = new_ctrl
+    self.ctrl = new_ctrl;
+    Ok(())
   }
 
-  fn is_final(&self) -> bool {
-    match self.ctrl.borrow() {
-      Ctrl::Value(v) => match v.borrow() {
-        Value::Num(_) | Value::Pack(_, _) => self.kont.is_empty(),
+  pub(crate) fn is_final(&self) -> bool {
+    match &self.ctrl {
+      Ctrl::Value(handle) => match self.heap.get(*handle) {
+        Value::Num(_) | Value::Pack(_, _) | Value::Array(_) => self.kont.is_empty(),
         _ => false,
       },
       _ => false,
     }
   }
 
-  pub fn run(&mut self, module: &'a Module) -> u64 {
-    let mut count = 0;
+  /// The name of the global about to be entered, if `Ctrl` currently holds a
+  /// saturated application of one. Lets the debugger implement breakpoints
+  /// without this module exposing `Ctrl`/`Value` to the rest of the crate.
+  pub(crate) fn pending_global(&self) -> Option<&'a Name> {
+    match &self.ctrl {
+      Ctrl::Value(handle) => match self.heap.get(*handle) {
+        Value::PAP(Prim::Global(name, _), _, 0) => Some(name),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  /// A compact, human-readable dump of the machine state: `Ctrl` with
+  /// globals shown by name and `Pack`s decoded instead of raw handles, the
+  /// de Bruijn `Env` as a numbered stack, and one line per continuation
+  /// frame.
+  pub(crate) fn describe(&self) -> String {
+    let mut out = format!("ctrl: {}\n", self.describe_ctrl());
+    out.push_str("env:\n");
+    for (i, handle) in self.env.stack.iter().enumerate() {
+      out.push_str(&format!(
+        "  {}: {}\n",
+        self.env.stack.len() - i,
+        self.describe_value(*handle)
+      ));
+    }
+    out.push_str("kont:\n");
+    for k in self.kont.iter().rev() {
+      out.push_str(&format!("  {}\n", self.describe_kont(k)));
+    }
+    out
+  }
+
+  fn describe_ctrl(&self) -> String {
+    match &self.ctrl {
+      Ctrl::Evaluating => String::from("<evaluating>"),
+      Ctrl::Expr(expr) => format!("expr {:?}", expr),
+      Ctrl::Value(handle) => self.describe_value(*handle),
+    }
+  }
+
+  fn describe_value(&self, handle: Handle) -> String {
+    match self.heap.get(handle) {
+      Value::Num(n) => n.to_string(),
+      Value::Pack(tag, args) => format!(
+        "Pack({}, [{}])",
+        tag,
+        args
+          .iter()
+          .map(|&h| self.describe_value(h))
+          .collect::<Vec<_>>()
+          .join(", ")
+      ),
+      Value::PAP(prim, args, missing) => {
+        format!("{} ({} applied, {} missing)", describe_prim(prim), args.len(), missing)
+      }
+      Value::Array(items) => format!(
+        "[{}]",
+        items
+          .iter()
+          .map(|&h| self.describe_value(h))
+          .collect::<Vec<_>>()
+          .join(", ")
+      ),
+    }
+  }
+
+  fn describe_kont(&self, k: &Kont<'a>) -> String {
+    match k {
+      Kont::Dump(env) => format!("dump ({} locals)", env.stack.len()),
+      Kont::Pop(n) => format!("pop {}", n),
+      Kont::Args(exprs) => format!("args ({} remaining)", exprs.len()),
+      Kont::Fun(prim, args, missing) => format!(
+        "fun {} ({} applied, {} missing)",
+        describe_prim(prim),
+        args.len(),
+        missing
+      ),
+      Kont::Match(altns) => format!("match ({} alternatives)", altns.len()),
+      Kont::Let(name, _) => format!("let {}", name),
+    }
+  }
+
+  fn render(&self, handle: Handle) -> RunResult {
+    match self.heap.get(handle) {
+      Value::Num(n) => RunResult::Num(*n),
+      Value::Pack(tag, args) => {
+        RunResult::Pack(*tag, args.iter().map(|&h| self.render(h)).collect())
+      }
+      Value::Array(items) => RunResult::Array(items.iter().map(|&h| self.render(h)).collect()),
+      Value::PAP(..) => unreachable!("is_final guarantees a concrete value"),
+    }
+  }
+
+  pub fn run(&mut self, module: &'a Module) -> Result<(RunResult, u64), RuntimeError<'a>> {
     while !self.is_final() {
-      self.step(module);
-      count += 1;
+      self.step(module)?;
     }
-    count
+    let handle = match &self.ctrl {
+      Ctrl::Value(handle) => *handle,
+      _ => unreachable!("is_final guarantees Ctrl::Value"),
+    };
+    Ok((self.render(handle), self.steps))
+  }
+}
+
+fn describe_prim(prim: &Prim) -> String {
+  match prim {
+    Prim::Global(name, _) => format!("global {}", name),
+    Prim::External(name) => format!("external {:?}", name),
+    Prim::Pack(tag, arity) => format!("pack {}/{}", tag, arity),
   }
 }
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::{Altn, Defn, Expr, External, Lambda, Module, Name};
+
+/// A `Global` reference with no matching definition in the `Module` being
+/// compiled.
+#[derive(Debug)]
+pub struct CompileError {
+  pub name: Name,
+}
+
+impl fmt::Display for CompileError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "reference to unknown global: {}", self.name)
+  }
+}
+
+impl std::error::Error for CompileError {}
+
+/// Index of a compiled global in a `Program`, interned once at compile time
+/// so that looking up a global at run time is an array index rather than a
+/// `HashMap::get` on its name.
+pub type GlobalId = u32;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Instr {
+  PushLocal(usize),
+  PushGlobal(GlobalId),
+  PushExternal(External),
+  PushPack { tag: usize, arity: usize },
+  PushNum(i64),
+  Apply(usize),
+  Enter,
+  Return,
+  /// Index into the enclosing `CompiledGlobal::jump_tables`. The table maps
+  /// a `Pack` tag to the offset of its alternative's code within the same
+  /// `CompiledGlobal::code`.
+  Match(usize),
+  Bind,
+  Pop(usize),
+  /// Unconditional jump used to skip over the other alternatives of a
+  /// `Match` once one of them has run.
+  Jump(usize),
+}
+
+#[derive(Debug)]
+pub struct CompiledGlobal {
+  pub name: Name,
+  pub arity: usize,
+  pub code: Vec<Instr>,
+  pub jump_tables: Vec<Vec<usize>>,
+}
+
+#[derive(Debug)]
+pub struct Program {
+  pub globals: Vec<CompiledGlobal>,
+  pub index: HashMap<Name, GlobalId>,
+}
+
+impl Program {
+  pub fn global_id(&self, name: &Name) -> Result<GlobalId, CompileError> {
+    self.index.get(name).copied().ok_or_else(|| CompileError { name: name.clone() })
+  }
+
+  pub fn global(&self, id: GlobalId) -> &CompiledGlobal {
+    &self.globals[id as usize]
+  }
+}
+
+struct Compiler<'a> {
+  index: &'a HashMap<Name, GlobalId>,
+  code: Vec<Instr>,
+  jump_tables: Vec<Vec<usize>>,
+}
+
+impl<'a> Compiler<'a> {
+  fn new(index: &'a HashMap<Name, GlobalId>) -> Self {
+    Compiler {
+      index,
+      code: Vec::new(),
+      jump_tables: Vec::new(),
+    }
+  }
+
+  fn compile_expr(&mut self, expr: &Expr) -> Result<(), CompileError> {
+    match expr {
+      Expr::Local { idx, .. } => self.code.push(Instr::PushLocal(*idx)),
+      Expr::Global { name } => {
+        let id = self.index.get(name).copied().ok_or_else(|| CompileError { name: name.clone() })?;
+        self.code.push(Instr::PushGlobal(id));
+      }
+      Expr::External { name } => self.code.push(Instr::PushExternal(*name)),
+      &Expr::Pack { tag, arity } => self.code.push(Instr::PushPack { tag, arity }),
+      &Expr::Num { int } => self.code.push(Instr::PushNum(int)),
+      Expr::Ap { fun, args } => {
+        self.compile_expr(fun)?;
+        for arg in args {
+          self.compile_expr(arg)?;
+        }
+        self.code.push(Instr::Apply(args.len()));
+      }
+      Expr::Let { defn, body } => {
+        let Defn { rhs, .. } = defn.as_ref();
+        self.compile_expr(rhs)?;
+        self.code.push(Instr::Bind);
+        self.compile_expr(body)?;
+        self.code.push(Instr::Pop(1));
+      }
+      Expr::Match { expr, altns } => {
+        self.compile_expr(expr)?;
+        let table_idx = self.jump_tables.len();
+        self.jump_tables.push(vec![0; altns.len()]);
+        self.code.push(Instr::Match(table_idx));
+        let mut exits = Vec::with_capacity(altns.len());
+        for (tag, Altn { binds, rhs }) in altns.iter().enumerate() {
+          self.jump_tables[table_idx][tag] = self.code.len();
+          self.compile_expr(rhs)?;
+          self.code.push(Instr::Pop(binds.len()));
+          exits.push(self.code.len());
+          self.code.push(Instr::Jump(usize::MAX));
+        }
+        let after = self.code.len();
+        for exit in exits {
+          self.code[exit] = Instr::Jump(after);
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+fn compile_lambda(name: &Name, lam: &Lambda, index: &HashMap<Name, GlobalId>) -> Result<CompiledGlobal, CompileError> {
+  let mut compiler = Compiler::new(index);
+  compiler.code.push(Instr::Enter);
+  compiler.compile_expr(&lam.body)?;
+  compiler.code.push(Instr::Return);
+  Ok(CompiledGlobal {
+    name: name.clone(),
+    arity: lam.binds.len(),
+    code: compiler.code,
+    jump_tables: compiler.jump_tables,
+  })
+}
+
+pub fn compile_module(module: &Module) -> Result<Program, CompileError> {
+  let index: HashMap<Name, GlobalId> = module
+    .keys()
+    .enumerate()
+    .map(|(id, name)| (name.clone(), id as GlobalId))
+    .collect();
+  let mut globals: Vec<Option<CompiledGlobal>> = (0..index.len()).map(|_| None).collect();
+  for (name, lam) in module {
+    let id = index[name] as usize;
+    globals[id] = Some(compile_lambda(name, lam, &index)?);
+  }
+  let globals = globals.into_iter().map(|g| g.expect("Every id is assigned exactly once")).collect();
+  Ok(Program { globals, index })
+}
@@ -0,0 +1,66 @@
+use std::io::{BufRead, Write};
+
+use crate::ast::{Module, Name};
+use crate::cek::State;
+use crate::error::RuntimeError;
+
+/// Wraps a `cek::State` with single-stepping, readable state dumps, and
+/// breakpoints that pause just before a named global is entered.
+pub struct Debugger<'a, In, Out> {
+  state: State<'a, In, Out>,
+  breakpoints: Vec<&'a Name>,
+}
+
+impl<'a, In: BufRead, Out: Write> Debugger<'a, In, Out> {
+  pub fn new(state: State<'a, In, Out>) -> Self {
+    Debugger {
+      state,
+      breakpoints: Vec::new(),
+    }
+  }
+
+  pub fn set_max_steps(&mut self, max_steps: Option<u64>) {
+    self.state.set_max_steps(max_steps);
+  }
+
+  pub fn break_on(&mut self, name: &'a Name) {
+    self.breakpoints.push(name);
+  }
+
+  pub fn is_final(&self) -> bool {
+    self.state.is_final()
+  }
+
+  pub fn steps(&self) -> u64 {
+    self.state.steps()
+  }
+
+  pub fn describe(&self) -> String {
+    self.state.describe()
+  }
+
+  /// Advances the machine by exactly one step.
+  pub fn step(&mut self, module: &'a Module) -> Result<(), RuntimeError<'a>> {
+    self.state.step(module)
+  }
+
+  fn at_breakpoint(&self) -> bool {
+    self
+      .state
+      .pending_global()
+      .map_or(false, |name| self.breakpoints.iter().any(|b| **b == *name))
+  }
+
+  /// Steps until the program finishes, a breakpoint is hit, or a trap is
+  /// raised. Returns `true` if it stopped at a breakpoint rather than
+  /// running to completion.
+  pub fn continue_to_final(&mut self, module: &'a Module) -> Result<bool, RuntimeError<'a>> {
+    while !self.is_final() {
+      self.step(module)?;
+      if self.at_breakpoint() {
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+}
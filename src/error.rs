@@ -0,0 +1,76 @@
+use crate::ast::{Expr, External};
+
+/// A trap raised by the CEK machine or an external instead of aborting the
+/// host process. Each variant carries the step count it happened at and,
+/// for the variants raised on a specific `Expr` or `External`, that `Expr`
+/// or `External`, to report a useful diagnostic to an embedder (REPL, web
+/// playground, ...).
+#[derive(Debug)]
+pub enum RuntimeError<'a> {
+  BadLocalIndex { expr: &'a Expr, step: u64 },
+  UnknownGlobal { expr: &'a Expr, step: u64 },
+  TypeMismatch { external: External, step: u64 },
+  WrongArity { external: External, found: usize, step: u64 },
+  DivByZero { external: External, step: u64 },
+  IndexOutOfBounds { external: External, index: i64, step: u64 },
+  /// An `Ap` with zero arguments — never produced by a well-formed `Module`,
+  /// but reachable from a hand-crafted or buggy one.
+  ArityMismatch { step: u64 },
+  /// The callee position of an application evaluated to something other
+  /// than a (possibly partial) function.
+  NotAFunction { step: u64 },
+  /// The scrutinee of a `Match` evaluated to something other than a `Pack`.
+  NotData { step: u64 },
+  /// A `Pack`'s tag has no corresponding alternative in the `Match` it hit.
+  TagOutOfRange { tag: usize, step: u64 },
+  StepLimit { step: u64 },
+}
+
+impl<'a> std::fmt::Display for RuntimeError<'a> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      RuntimeError::BadLocalIndex { expr, step } => {
+        write!(f, "bad de Bruijn index in {:?} (step {})", expr, step)
+      }
+      RuntimeError::UnknownGlobal { expr, step } => {
+        write!(f, "unknown global in {:?} (step {})", expr, step)
+      }
+      RuntimeError::TypeMismatch { external, step } => {
+        write!(f, "type mismatch evaluating {:?} (step {})", external, step)
+      }
+      RuntimeError::WrongArity { external, found, step } => write!(
+        f,
+        "found {} arguments for {:?}/{} (step {})",
+        found,
+        external,
+        external.arity(),
+        step
+      ),
+      RuntimeError::DivByZero { external, step } => {
+        write!(f, "division by zero in {:?} (step {})", external, step)
+      }
+      RuntimeError::IndexOutOfBounds { external, index, step } => write!(
+        f,
+        "index {} out of bounds in {:?} (step {})",
+        index, external, step
+      ),
+      RuntimeError::ArityMismatch { step } => {
+        write!(f, "applied a function to zero arguments (step {})", step)
+      }
+      RuntimeError::NotAFunction { step } => {
+        write!(f, "applied a non-function value (step {})", step)
+      }
+      RuntimeError::NotData { step } => {
+        write!(f, "pattern-matched a non-data value (step {})", step)
+      }
+      RuntimeError::TagOutOfRange { tag, step } => {
+        write!(f, "tag {} has no matching alternative (step {})", tag, step)
+      }
+      RuntimeError::StepLimit { step } => {
+        write!(f, "step limit reached after {} steps", step)
+      }
+    }
+  }
+}
+
+impl<'a> std::error::Error for RuntimeError<'a> {}
@@ -0,0 +1,123 @@
+use crate::val::Value;
+
+/// A lightweight index into a `Heap`'s slots, replacing `Rc<Value>` pointers.
+/// Copying a `Handle` is as cheap as copying a `u32`; no reference counting
+/// is involved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle(u32);
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GcStats {
+  pub collections: u64,
+  pub bytes_reclaimed: u64,
+}
+
+/// The heap size, in live values, at which a fresh `Heap` triggers its first
+/// collection. Shared by both the CEK machine and the VM so neither has to
+/// invent its own tuning constant.
+pub const DEFAULT_GC_THRESHOLD: usize = 4096;
+
+/// An arena that owns every `Value` allocated while a program runs. Values
+/// are addressed by `Handle` rather than borrowed or reference-counted, so
+/// the hot paths that used to deep-clone `Vec<Rc<Value>>` now just copy
+/// handles. `P` is the callable payload a `Value::PAP` carries — an AST-level
+/// `val::Prim` for the CEK machine, a bytecode `vm::Callee` for the VM — and
+/// is opaque to the heap itself, which never needs to look inside it.
+/// Garbage is found with a mark-sweep pass over a caller-supplied root set;
+/// see `State::collect_roots` and `Vm::collect_roots` for what counts as a
+/// root.
+pub struct Heap<P> {
+  slots: Vec<Option<Value<P>>>,
+  marks: Vec<bool>,
+  free: Vec<u32>,
+  threshold: usize,
+  stats: GcStats,
+}
+
+impl<P> Heap<P> {
+  pub fn new(threshold: usize) -> Self {
+    Heap {
+      slots: Vec::new(),
+      marks: Vec::new(),
+      free: Vec::new(),
+      threshold,
+      stats: GcStats::default(),
+    }
+  }
+
+  pub fn alloc(&mut self, value: Value<P>) -> Handle {
+    if let Some(idx) = self.free.pop() {
+      self.slots[idx as usize] = Some(value);
+      Handle(idx)
+    } else {
+      let idx = self.slots.len() as u32;
+      self.slots.push(Some(value));
+      self.marks.push(false);
+      Handle(idx)
+    }
+  }
+
+  pub fn get(&self, handle: Handle) -> &Value<P> {
+    self.slots[handle.0 as usize]
+      .as_ref()
+      .expect("Dangling handle")
+  }
+
+  /// Like `get`, but mutable. Used by externals (e.g. `update`) that mutate
+  /// an array value in place rather than allocating a new one.
+  pub fn get_mut(&mut self, handle: Handle) -> &mut Value<P> {
+    self.slots[handle.0 as usize]
+      .as_mut()
+      .expect("Dangling handle")
+  }
+
+  pub fn live_count(&self) -> usize {
+    self.slots.len() - self.free.len()
+  }
+
+  pub fn should_collect(&self) -> bool {
+    self.live_count() >= self.threshold
+  }
+
+  pub fn gc_stats(&self) -> GcStats {
+    self.stats
+  }
+
+  /// Marks every value transitively reachable from `roots`, then frees every
+  /// unmarked slot. Any `Handle` not in `roots` and not reachable from one
+  /// must not be dereferenced again after this call.
+  pub fn collect(&mut self, roots: impl IntoIterator<Item = Handle>) {
+    for mark in self.marks.iter_mut() {
+      *mark = false;
+    }
+
+    let mut pending: Vec<Handle> = roots.into_iter().collect();
+    while let Some(handle) = pending.pop() {
+      let idx = handle.0 as usize;
+      if self.marks[idx] {
+        continue;
+      }
+      self.marks[idx] = true;
+      match self.slots[idx].as_ref().expect("Dangling handle") {
+        Value::Num(_) => {}
+        Value::Pack(_, args) => pending.extend_from_slice(args),
+        Value::PAP(_, args, _) => pending.extend_from_slice(args),
+        Value::Array(items) => pending.extend_from_slice(items),
+      }
+    }
+
+    let mut reclaimed = 0u64;
+    for (idx, slot) in self.slots.iter_mut().enumerate() {
+      if slot.is_some() && !self.marks[idx] {
+        *slot = None;
+        self.free.push(idx as u32);
+        reclaimed += 1;
+      }
+    }
+
+    self.stats.collections += 1;
+    self.stats.bytes_reclaimed += reclaimed * std::mem::size_of::<Value<P>>() as u64;
+    // Avoid immediately re-triggering a collection that frees almost nothing.
+    self.threshold = self.threshold.max(self.live_count() * 2);
+  }
+}
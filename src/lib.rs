@@ -0,0 +1,14 @@
+pub mod ast;
+pub mod cek;
+pub mod compile;
+pub mod debug;
+pub mod error;
+pub mod heap;
+pub mod val;
+pub mod vm;
+
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
+
+#[cfg(test)]
+mod tests;
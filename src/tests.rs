@@ -1,5 +1,8 @@
 use crate::ast;
 use crate::cek;
+use crate::compile;
+use crate::error::RuntimeError;
+use crate::vm;
 
 fn test_text(name: &str, input: &str, expected_output: &str) {
   let file = "test/".to_string() + name + ".pub";
@@ -8,7 +11,7 @@ fn test_text(name: &str, input: &str, expected_output: &str) {
   let mut input = input.as_bytes();
   let mut actual_output = Vec::new();
   let mut state = cek::State::from_expr(&entry_point, &mut input, &mut actual_output);
-  let _count = state.run(&module);
+  let _ = state.run(&module).unwrap();
   let actual_output = std::str::from_utf8(&actual_output).unwrap();
   assert!(actual_output == expected_output, "\n  expected output: {:?}\n    actual output: {:?}\n", expected_output, actual_output);
 }
@@ -38,6 +41,59 @@ fn test_sort(name: &str) {
   result.expect("test_sort failed");
 }
 
+fn test_conformance(name: &str, input: &str) {
+  let file = "test/".to_string() + name + ".pub";
+  let module: ast::Module = ast::load_module(file).unwrap();
+  let entry_point = ast::Expr::entry_point();
+
+  let mut cek_input = input.as_bytes();
+  let mut cek_output = Vec::new();
+  let mut state = cek::State::from_expr(&entry_point, &mut cek_input, &mut cek_output);
+  let _ = state.run(&module).unwrap();
+  let cek_output = std::str::from_utf8(&cek_output).unwrap();
+
+  let program = compile::compile_module(&module).unwrap();
+  let main_id = program.global_id(&String::from("main")).unwrap();
+  let mut vm_input = input.as_bytes();
+  let mut vm_output = Vec::new();
+  let mut machine = vm::Vm::new(&program, &mut vm_input, &mut vm_output);
+  let unit = machine.alloc(vm::Value::unit());
+  let _result = machine.run(main_id, vec![unit]).unwrap();
+  let vm_output = std::str::from_utf8(&vm_output).unwrap();
+
+  assert!(
+    cek_output == vm_output,
+    "\n  cek output: {:?}\n   vm output: {:?}\n",
+    cek_output,
+    vm_output
+  );
+}
+
+#[test]
+fn conformance_hello() {
+  test_conformance("hello", "");
+}
+
+#[test]
+fn conformance_rev() {
+  test_conformance("rev", "abc");
+}
+
+#[test]
+fn conformance_monad_io() {
+  test_conformance("monad_io", "3\n2\n");
+}
+
+#[test]
+fn conformance_wildcard() {
+  test_conformance("wildcard", "7\n13\n");
+}
+
+#[test]
+fn conformance_queens() {
+  test_conformance("queens", "8\n");
+}
+
 #[test]
 fn hello() {
   test_text("hello", "", "Hello World!\n");
@@ -63,6 +119,49 @@ fn queens() {
   test_numeric("queens", &[8], &[92]);
 }
 
+#[test]
+fn conformance_arrays() {
+  test_conformance("arrays", "");
+}
+
+#[test]
+fn arrays() {
+  test_text("arrays", "", "150\n5\n");
+}
+
+#[test]
+fn array_index_out_of_bounds_traps() {
+  let file = "test/array_oob.pub";
+  let module: ast::Module = ast::load_module(file).unwrap();
+  let entry_point = ast::Expr::entry_point();
+  let mut input: &[u8] = &[];
+  let mut output = Vec::new();
+  let mut state = cek::State::from_expr(&entry_point, &mut input, &mut output);
+  match state.run(&module) {
+    Err(RuntimeError::IndexOutOfBounds { index, .. }) => assert_eq!(index, 10),
+    other => panic!("expected IndexOutOfBounds, got {:?}", other),
+  }
+}
+
+/// Runs a recursion-heavy fixture with a small `gc_threshold` so `collect`
+/// actually runs, and checks the result is still correct once it has.
+#[test]
+fn gc_collects_under_pressure() {
+  let file = "test/gc_stress.pub";
+  let module: ast::Module = ast::load_module(file).unwrap();
+  let entry_point = ast::Expr::entry_point();
+  let mut input: &[u8] = &[];
+  let mut output = Vec::new();
+  let mut state = cek::State::with_gc_threshold(&entry_point, &mut input, &mut output, 16);
+  let _ = state.run(&module).unwrap();
+  let output = std::str::from_utf8(&output).unwrap();
+  assert_eq!(output, "5050\n");
+  assert!(
+    state.gc_stats().collections > 0,
+    "expected at least one collection at a threshold of 16"
+  );
+}
+
 #[test]
 fn qsort() {
   test_sort("qsort");
@@ -1,7 +1,8 @@
 use std::io::{BufRead, Write};
-use std::rc::Rc;
 
 use crate::ast::{External, Lambda, Name};
+use crate::error::RuntimeError;
+use crate::heap::{Handle, Heap};
 
 #[derive(Debug, Copy, Clone)]
 pub enum Prim<'a> {
@@ -10,67 +11,173 @@ pub enum Prim<'a> {
   Pack(usize, usize),
 }
 
+/// A heap-allocated value, generic over `P`, the payload a partial
+/// application (`PAP`) carries for the callee it's waiting to saturate.
+/// `P` is `Prim<'a>` for the CEK machine (an AST-level callable) and
+/// `vm::Callee` for the VM (a bytecode-level one); neither `Value` nor
+/// `Heap` needs to know which, since they only ever move `P` around.
 #[derive(Debug, Clone)]
-pub enum Value<'a> {
+pub enum Value<P> {
   Num(i64),
-  Pack(usize, Vec<Rc<Value<'a>>>),
-  PAP(Prim<'a>, Vec<Rc<Value<'a>>>, usize),
+  Pack(usize, Vec<Handle>),
+  PAP(P, Vec<Handle>, usize),
+  Array(Vec<Handle>),
 }
 
-impl<'a> Value<'a> {
-  pub fn rc_unit() -> Rc<Self> {
-    Rc::new(Value::Pack(0, Vec::new()))
+impl<P> Value<P> {
+  pub fn unit() -> Self {
+    Value::Pack(0, Vec::new())
   }
 
-  pub fn rc_from_bool(b: bool) -> Rc<Self> {
-    Rc::new(Value::Pack(b.into(), Vec::new()))
+  pub fn from_bool(b: bool) -> Self {
+    Value::Pack(b.into(), Vec::new())
   }
 
-  pub fn rc_from_i64(n: i64) -> Rc<Self> {
-    Rc::new(Value::Num(n))
+  pub fn from_i64(n: i64) -> Self {
+    Value::Num(n)
   }
 
-  pub fn as_i64(&self) -> i64 {
+  pub fn as_i64(&self) -> Option<i64> {
     match self {
-      Value::Num(n) => *n,
-      _ => panic!("Expected Int, found {:?}", self),
+      Value::Num(n) => Some(*n),
+      _ => None,
     }
   }
 
-  pub fn eval_external<In: BufRead, Out: Write>(name: External, args: &Vec<Rc<Self>>, input: &mut In, output: &mut Out) -> Rc<Self> {
+  pub fn as_array(&self) -> Option<&Vec<Handle>> {
+    match self {
+      Value::Array(items) => Some(items),
+      _ => None,
+    }
+  }
+
+  pub fn eval_external<'a, In: BufRead, Out: Write>(
+    heap: &mut Heap<P>,
+    name: External,
+    args: &[Handle],
+    input: &mut In,
+    output: &mut Out,
+    step: u64,
+  ) -> Result<Handle, RuntimeError<'a>> {
     use self::External::*;
     if args.len() != name.arity() {
-      panic!(
-        "Found {} arguments for {:?}/{}",
-        args.len(),
-        name,
-        name.arity()
-      );
+      return Err(RuntimeError::WrongArity {
+        external: name,
+        found: args.len(),
+        step,
+      });
     }
-    match name {
-      add => Value::rc_from_i64(args[0].as_i64() + args[1].as_i64()),
-      sub => Value::rc_from_i64(args[0].as_i64() - args[1].as_i64()),
-      mul => Value::rc_from_i64(args[0].as_i64() * args[1].as_i64()),
-      neg => Value::rc_from_i64(-args[0].as_i64()),
-      eq => Value::rc_from_bool(args[0].as_i64() == args[1].as_i64()),
-      le => Value::rc_from_bool(args[0].as_i64() <= args[1].as_i64()),
-      lt => Value::rc_from_bool(args[0].as_i64() < args[1].as_i64()),
-      gt => Value::rc_from_bool(args[0].as_i64() > args[1].as_i64()),
-      ge => Value::rc_from_bool(args[0].as_i64() >= args[1].as_i64()),
-      chr => Value::rc_from_i64(args[0].as_i64() & 0xFF),
-      ord => Value::rc_from_i64(args[0].as_i64()),
+    let arg = |i: usize| -> Result<i64, RuntimeError<'a>> {
+      heap
+        .get(args[i])
+        .as_i64()
+        .ok_or(RuntimeError::TypeMismatch { external: name, step })
+    };
+    let divisor = |i: usize| -> Result<i64, RuntimeError<'a>> {
+      match arg(i)? {
+        0 => Err(RuntimeError::DivByZero { external: name, step }),
+        n => Ok(n),
+      }
+    };
+    let shift = |i: usize| -> Result<i64, RuntimeError<'a>> {
+      match arg(i)? {
+        n @ 0..=63 => Ok(n),
+        _ => Err(RuntimeError::TypeMismatch { external: name, step }),
+      }
+    };
+    Ok(match name {
+      add => heap.alloc(Value::from_i64(arg(0)? + arg(1)?)),
+      sub => heap.alloc(Value::from_i64(arg(0)? - arg(1)?)),
+      mul => heap.alloc(Value::from_i64(arg(0)? * arg(1)?)),
+      neg => heap.alloc(Value::from_i64(-arg(0)?)),
+      eq => heap.alloc(Value::from_bool(arg(0)? == arg(1)?)),
+      le => heap.alloc(Value::from_bool(arg(0)? <= arg(1)?)),
+      lt => heap.alloc(Value::from_bool(arg(0)? < arg(1)?)),
+      gt => heap.alloc(Value::from_bool(arg(0)? > arg(1)?)),
+      ge => heap.alloc(Value::from_bool(arg(0)? >= arg(1)?)),
+      chr => heap.alloc(Value::from_i64(arg(0)? & 0xFF)),
+      ord => heap.alloc(Value::from_i64(arg(0)?)),
+      div => heap.alloc(Value::from_i64(arg(0)? / divisor(1)?)),
+      mod_ => heap.alloc(Value::from_i64(arg(0)? % divisor(1)?)),
+      and => heap.alloc(Value::from_i64(arg(0)? & arg(1)?)),
+      or => heap.alloc(Value::from_i64(arg(0)? | arg(1)?)),
+      xor => heap.alloc(Value::from_i64(arg(0)? ^ arg(1)?)),
+      shl => heap.alloc(Value::from_i64(arg(0)? << shift(1)?)),
+      shr => heap.alloc(Value::from_i64(arg(0)? >> shift(1)?)),
+      newarr => {
+        let len = arg(0)?;
+        if len < 0 {
+          return Err(RuntimeError::TypeMismatch { external: name, step });
+        }
+        heap.alloc(Value::Array(vec![args[1]; len as usize]))
+      }
+      index => {
+        let i = arg(1)?;
+        let item = match heap.get(args[0]) {
+          Value::Array(items) => usize::try_from(i).ok().and_then(|idx| items.get(idx)).copied(),
+          _ => return Err(RuntimeError::TypeMismatch { external: name, step }),
+        };
+        item.ok_or(RuntimeError::IndexOutOfBounds { external: name, index: i, step })?
+      }
+      update => {
+        let i = arg(1)?;
+        match heap.get_mut(args[0]) {
+          Value::Array(items) => {
+            let slot = usize::try_from(i)
+              .ok()
+              .and_then(|idx| items.get_mut(idx))
+              .ok_or(RuntimeError::IndexOutOfBounds { external: name, index: i, step })?;
+            *slot = args[2];
+          }
+          _ => return Err(RuntimeError::TypeMismatch { external: name, step }),
+        }
+        heap.alloc(Value::unit())
+      }
+      length => {
+        let len = match heap.get(args[0]) {
+          Value::Array(items) => items.len() as i64,
+          _ => return Err(RuntimeError::TypeMismatch { external: name, step }),
+        };
+        heap.alloc(Value::from_i64(len))
+      }
+      putstr => {
+        let items = match heap.get(args[0]) {
+          Value::Array(items) => items.clone(),
+          _ => return Err(RuntimeError::TypeMismatch { external: name, step }),
+        };
+        let mut line = String::with_capacity(items.len());
+        for item in items {
+          let code = heap
+            .get(item)
+            .as_i64()
+            .ok_or(RuntimeError::TypeMismatch { external: name, step })?;
+          line.push(code as u8 as char);
+        }
+        writeln!(output, "{}", line);
+        heap.alloc(Value::unit())
+      }
+      getstr => {
+        let mut line = String::new();
+        input.read_line(&mut line).expect("Failed to read line");
+        let items = line
+          .trim_end_matches('\n')
+          .chars()
+          .map(|c| heap.alloc(Value::from_i64(c as i64)))
+          .collect();
+        heap.alloc(Value::Array(items))
+      }
       puti => {
-        writeln!(output, "{}", args[0].as_i64());
-        Value::rc_unit()
+        writeln!(output, "{}", arg(0)?);
+        heap.alloc(Value::unit())
       }
       putc => {
-        write!(output, "{}", args[0].as_i64() as u8 as char);
-        Value::rc_unit()
+        write!(output, "{}", arg(0)? as u8 as char);
+        heap.alloc(Value::unit())
       }
       geti => {
         let mut line = String::new();
         input.read_line(&mut line).expect("Failed to read line");
-        Value::rc_from_i64(line.trim().parse().expect("Input not a number"))
+        heap.alloc(Value::from_i64(line.trim().parse().expect("Input not a number")))
       }
       getc => {
         let mut buffer = [0];
@@ -78,9 +185,9 @@ impl<'a> Value<'a> {
           Ok(()) => buffer[0] as i64,
           Err(_) => -1,
         };
-        Value::rc_from_i64(n)
+        heap.alloc(Value::from_i64(n))
       }
-      seq => Rc::clone(&args[1]),
-    }
+      seq => args[1],
+    })
   }
 }
@@ -0,0 +1,233 @@
+use std::io::{BufRead, Write};
+
+use crate::ast::External;
+use crate::compile::{CompiledGlobal, GlobalId, Instr, Program};
+use crate::error::RuntimeError;
+use crate::heap::{Handle, Heap, DEFAULT_GC_THRESHOLD};
+pub use crate::val::Value;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Callee {
+  Global(GlobalId),
+  External(External),
+  Pack(usize, usize),
+}
+
+impl Callee {
+  fn arity(self, program: &Program) -> usize {
+    match self {
+      Callee::Global(id) => program.global(id).arity,
+      Callee::External(name) => name.arity(),
+      Callee::Pack(_, arity) => arity,
+    }
+  }
+}
+
+/// A suspended call: the global we entered, the program counter to resume
+/// at once it returns, and the locals stack depth to restore. This replaces
+/// `Kont::Dump`'s saved `Env`.
+struct Frame {
+  global: GlobalId,
+  pc: usize,
+  base: usize,
+}
+
+pub struct Vm<'a, In, Out> {
+  program: &'a Program,
+  heap: Heap<Callee>,
+  locals: Vec<Handle>,
+  operands: Vec<Handle>,
+  frames: Vec<Frame>,
+  steps: u64,
+  input: &'a mut In,
+  output: &'a mut Out,
+}
+
+impl<'a, In: BufRead, Out: Write> Vm<'a, In, Out> {
+  pub fn new(program: &'a Program, input: &'a mut In, output: &'a mut Out) -> Self {
+    Self::with_gc_threshold(program, input, output, DEFAULT_GC_THRESHOLD)
+  }
+
+  pub fn with_gc_threshold(program: &'a Program, input: &'a mut In, output: &'a mut Out, gc_threshold: usize) -> Self {
+    Vm {
+      program,
+      heap: Heap::new(gc_threshold),
+      locals: Vec::new(),
+      operands: Vec::new(),
+      frames: Vec::new(),
+      steps: 0,
+      input,
+      output,
+    }
+  }
+
+  /// Allocates `value` on the VM's own heap, returning the `Handle` its
+  /// caller needs to pass it in as an argument to `run`. Lets a caller build
+  /// the entry point's argument (e.g. the unit value `main` is applied to)
+  /// without this module exposing `Heap` itself.
+  pub fn alloc(&mut self, value: Value<Callee>) -> Handle {
+    self.heap.alloc(value)
+  }
+
+  fn local(&self, idx: usize) -> Handle {
+    self.locals[self.locals.len() - idx]
+  }
+
+  /// Every handle the garbage collector must treat as live: everything on
+  /// the locals stack and everything on the operand stack. Unlike the CEK
+  /// machine's continuation frames, `Frame` holds only a `pc` and stack
+  /// depths, no handles, so it contributes nothing.
+  fn collect_roots(&self) -> Vec<Handle> {
+    let mut roots = Vec::with_capacity(self.locals.len() + self.operands.len());
+    roots.extend_from_slice(&self.locals);
+    roots.extend_from_slice(&self.operands);
+    roots
+  }
+
+  fn maybe_collect(&mut self) {
+    if self.heap.should_collect() {
+      let roots = self.collect_roots();
+      self.heap.collect(roots);
+    }
+  }
+
+  fn apply(&mut self, fun: Handle, mut args: Vec<Handle>) -> Result<Handle, RuntimeError<'a>> {
+    let (callee, mut have, missing) = match self.heap.get(fun) {
+      Value::PAP(callee, have, missing) => (*callee, have.clone(), *missing),
+      _ => return Err(RuntimeError::NotAFunction { step: self.steps }),
+    };
+    if args.len() < missing {
+      let missing = missing - args.len();
+      have.append(&mut args);
+      return Ok(self.heap.alloc(Value::PAP(callee, have, missing)));
+    }
+    let extra = args.split_off(missing);
+    have.append(&mut args);
+    // Keep the surplus arguments on the operand stack while the saturated
+    // call runs, so a collection inside it treats them as roots.
+    let extra_base = self.operands.len();
+    self.operands.extend_from_slice(&extra);
+    let result = self.enter(callee, have)?;
+    let extra = self.operands.split_off(extra_base);
+    if extra.is_empty() {
+      Ok(result)
+    } else {
+      self.apply(result, extra)
+    }
+  }
+
+  fn enter(&mut self, callee: Callee, args: Vec<Handle>) -> Result<Handle, RuntimeError<'a>> {
+    match callee {
+      Callee::Pack(tag, _arity) => Ok(self.heap.alloc(Value::Pack(tag, args))),
+      Callee::External(name) => {
+        let step = self.steps;
+        Value::eval_external(&mut self.heap, name, &args, self.input, self.output, step)
+      }
+      Callee::Global(id) => {
+        let base = self.locals.len();
+        self.locals.extend(args);
+        self.run_global(id, base)
+      }
+    }
+  }
+
+  /// Runs one global's bytecode to completion, starting with its arguments
+  /// already pushed onto `self.locals` at `base`. On `Return`, control goes
+  /// back to the caller's saved frame if there is one, mirroring `Kont::Dump`.
+  ///
+  /// `run_global` recurses (through `Apply` -> `apply` -> `enter`) for every
+  /// called global, so `self.frames` holds every caller's frame below this
+  /// invocation's own. `depth` is the frame count *before* we push ours, and
+  /// we must return as soon as we've popped back down to it -- not merely
+  /// when `self.frames` happens to be empty, which is only true for the
+  /// outermost call.
+  fn run_global(&mut self, id: GlobalId, base: usize) -> Result<Handle, RuntimeError<'a>> {
+    let depth = self.frames.len();
+    self.frames.push(Frame { global: id, pc: 0, base });
+    loop {
+      self.maybe_collect();
+      self.steps += 1;
+      let frame = self.frames.last().expect("No active frame");
+      let global: &CompiledGlobal = self.program.global(frame.global);
+      let pc = frame.pc;
+      match global.code[pc] {
+        Instr::PushLocal(idx) => {
+          let v = self.local(idx);
+          self.operands.push(v);
+        }
+        Instr::PushGlobal(id) => {
+          let arity = self.program.global(id).arity;
+          let v = self.heap.alloc(Value::PAP(Callee::Global(id), Vec::new(), arity));
+          self.operands.push(v);
+        }
+        Instr::PushExternal(name) => {
+          let v = self.heap.alloc(Value::PAP(Callee::External(name), Vec::new(), name.arity()));
+          self.operands.push(v);
+        }
+        Instr::PushPack { tag, arity } => {
+          let v = self.heap.alloc(Value::PAP(Callee::Pack(tag, arity), Vec::new(), arity));
+          self.operands.push(v);
+        }
+        Instr::PushNum(n) => {
+          let v = self.heap.alloc(Value::from_i64(n));
+          self.operands.push(v);
+        }
+        Instr::Apply(n) => {
+          let args = self.operands.split_off(self.operands.len() - n);
+          let fun = self.operands.pop().expect("Empty operand stack");
+          self.frames.last_mut().unwrap().pc = pc + 1;
+          let result = self.apply(fun, args)?;
+          self.operands.push(result);
+          continue;
+        }
+        Instr::Bind => {
+          let v = self.operands.pop().expect("Empty operand stack");
+          self.locals.push(v);
+        }
+        Instr::Pop(n) => {
+          let new_len = self.locals.len() - n;
+          self.locals.truncate(new_len);
+        }
+        Instr::Match(table_idx) => {
+          let v = self.operands.pop().expect("Empty operand stack");
+          match self.heap.get(v) {
+            Value::Pack(tag, args) => {
+              let tag = *tag;
+              self.locals.extend_from_slice(args);
+              let target = match global.jump_tables[table_idx].get(tag) {
+                Some(&target) => target,
+                None => return Err(RuntimeError::TagOutOfRange { tag, step: self.steps }),
+              };
+              self.frames.last_mut().unwrap().pc = target;
+              continue;
+            }
+            _ => return Err(RuntimeError::NotData { step: self.steps }),
+          }
+        }
+        Instr::Jump(target) => {
+          self.frames.last_mut().unwrap().pc = target;
+          continue;
+        }
+        Instr::Enter => {}
+        Instr::Return => {
+          let result = self.operands.pop().expect("Empty operand stack");
+          let frame = self.frames.pop().expect("No active frame");
+          self.locals.truncate(frame.base);
+          if self.frames.len() == depth {
+            return Ok(result);
+          }
+          self.operands.push(result);
+          continue;
+        }
+      }
+      self.frames.last_mut().unwrap().pc += 1;
+    }
+  }
+
+  pub fn run(&mut self, entry: GlobalId, args: Vec<Handle>) -> Result<Handle, RuntimeError<'a>> {
+    let callee = Callee::Global(entry);
+    let arity = callee.arity(self.program);
+    let fun = self.heap.alloc(Value::PAP(callee, Vec::new(), arity));
+    self.apply(fun, args)
+  }
+}
@@ -0,0 +1,35 @@
+use wasm_bindgen::prelude::*;
+
+use crate::ast::{self, Expr};
+use crate::cek;
+
+/// What the playground gets back for one successful run: everything the
+/// program wrote to its (in-memory) stdout, plus the step count. A trap is
+/// not reported here but as the `Err` of `run`.
+#[derive(serde::Serialize)]
+pub struct RunOutput {
+  pub output: String,
+  pub steps: u64,
+}
+
+/// Runs `module_json` (the JSON a `Module` deserializes from) against
+/// `input` as the program's stdin, with no filesystem or real stdio
+/// involved, and returns the captured stdout plus step count. Errors
+/// (malformed JSON, a `RuntimeError` trap, non-UTF-8 output) are reported
+/// as a `JsValue` string rather than panicking the wasm module.
+#[wasm_bindgen]
+pub fn run(module_json: &str, input: &str) -> Result<JsValue, JsValue> {
+  let module = ast::load_module_str(module_json).map_err(|err| JsValue::from_str(&err.to_string()))?;
+  let entry_point = Expr::entry_point();
+
+  let mut input = input.as_bytes();
+  let mut output = Vec::new();
+  let mut state = cek::State::from_expr(&entry_point, &mut input, &mut output);
+  let (_result, steps) = state
+    .run(&module)
+    .map_err(|err| JsValue::from_str(&err.to_string()))?;
+  let output = String::from_utf8(output).map_err(|err| JsValue::from_str(&err.to_string()))?;
+
+  serde_wasm_bindgen::to_value(&RunOutput { output, steps })
+    .map_err(|err| JsValue::from_str(&err.to_string()))
+}